@@ -1,42 +1,487 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tauri::Manager;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{Emitter, Manager, RunEvent, WebviewUrl, WebviewWindowBuilder, WindowEvent, Wry};
+use tauri_plugin_shell::process::{CommandChild, CommandEvent};
 use tauri_plugin_shell::ShellExt;
-use tauri_plugin_shell::process::CommandEvent;
+use tokio::sync::{oneshot, Notify};
+
+/// Payload for the `server://stdout` and `server://stderr` events.
+#[derive(Clone, Serialize)]
+struct LogEvent {
+    line: String,
+    timestamp: u64,
+}
+
+/// Payload for the `server://status` event.
+#[derive(Clone, Serialize)]
+struct StatusEvent {
+    state: &'static str,
+    code: Option<i32>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Initial delay before the first restart attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound the backoff is capped at once it has doubled enough times.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// If the child stays alive at least this long, the backoff resets to `INITIAL_BACKOFF`.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(10);
+/// Default for `max_retries` when `AGENT_UAC_MAX_RETRIES` isn't set. Give up and
+/// surface a fatal error after this many consecutive failed restarts.
+const DEFAULT_MAX_RETRIES: u32 = 10;
+/// Env var overriding `DEFAULT_MAX_RETRIES`, read once at startup.
+const MAX_RETRIES_ENV_VAR: &str = "AGENT_UAC_MAX_RETRIES";
+/// How long to wait for the sidecar's readiness line before showing an error window.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Sentinel line the sidecar prints to stdout once it is listening, e.g. `SERVER_READY 41231`.
+const READY_PREFIX: &str = "SERVER_READY ";
+
+/// Managed state holding the currently running sidecar child (if any), the
+/// intentional-shutdown flag the supervisor checks before restarting, the
+/// dynamically assigned port once known, and the one-shot sender used to
+/// unblock the startup readiness wait.
+struct ServerState {
+    child: Mutex<Option<CommandChild>>,
+    shutting_down: Arc<AtomicBool>,
+    port: Mutex<Option<u16>>,
+    ready_tx: Mutex<Option<oneshot::Sender<()>>>,
+    tray_status_item: Mutex<Option<MenuItem<Wry>>>,
+    /// Consecutive-restart ceiling before the supervisor gives up; configurable
+    /// via `AGENT_UAC_MAX_RETRIES` so deployments can tune it without a rebuild.
+    max_retries: AtomicU32,
+    /// Set once the supervisor has exhausted `max_retries` and stopped trying.
+    gave_up: AtomicBool,
+    /// Interrupts a backoff sleep in `schedule_retry` so a manual restart takes
+    /// effect immediately instead of waiting out the current delay.
+    restart_notify: Arc<Notify>,
+}
+
+impl ServerState {
+    fn new() -> Self {
+        let max_retries = std::env::var(MAX_RETRIES_ENV_VAR)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_RETRIES);
+        Self {
+            child: Mutex::new(None),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            port: Mutex::new(None),
+            ready_tx: Mutex::new(None),
+            tray_status_item: Mutex::new(None),
+            max_retries: AtomicU32::new(max_retries),
+            gave_up: AtomicBool::new(false),
+            restart_notify: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// Updates the tray's status menu item and tooltip to reflect the sidecar's
+/// current lifecycle state (mirrors the states emitted on `server://status`).
+fn set_tray_status(app: &tauri::AppHandle, state: &str) {
+    let label = match state {
+        "starting" => "Server: starting…",
+        "ready" => "Server: ready",
+        "terminated" => "Server: stopped",
+        "fatal" => "Server: failed — restart needed",
+        other => other,
+    };
+    if let Some(item) = &*app.state::<ServerState>().tray_status_item.lock().unwrap() {
+        let _ = item.set_text(label);
+    }
+    if let Some(tray) = app.tray_by_id("main-tray") {
+        let _ = tray.set_tooltip(Some(label));
+    }
+}
+
+/// Shows the (single, reused) error window, creating it if it doesn't exist yet.
+fn show_error_window(app: &tauri::AppHandle, title: &str) {
+    match app.get_webview_window("error") {
+        Some(window) => {
+            let _ = window.set_title(title);
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        None => {
+            let _ = WebviewWindowBuilder::new(app, "error", WebviewUrl::App("error.html".into()))
+                .title(title)
+                .inner_size(360.0, 200.0)
+                .build();
+        }
+    }
+}
+
+/// Kills the managed sidecar child, if one is running. Node may have forked
+/// worker processes of its own; `CommandChild::kill` only terminates the
+/// immediate child, so orphaned workers are a known limitation until the
+/// sidecar is taught to tear down its own children on SIGTERM.
+fn kill_server(state: &ServerState) {
+    if let Some(child) = state.child.lock().unwrap().take() {
+        if let Err(err) = child.kill() {
+            eprintln!("[server] failed to kill sidecar: {err}");
+        }
+    }
+}
+
+/// Builds the system tray: a disabled status line, "Restart server", "Show
+/// logs", and "Quit". Menu events are wired up to reuse the existing
+/// supervisor/teardown logic rather than duplicating it.
+fn build_tray(app: &tauri::AppHandle) -> tauri::Result<()> {
+    let status_item = MenuItem::with_id(app, "status", "Server: starting…", false, None::<&str>)?;
+    let restart_item = MenuItem::with_id(app, "restart", "Restart server", true, None::<&str>)?;
+    let logs_item = MenuItem::with_id(app, "logs", "Show logs", true, None::<&str>)?;
+    let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &status_item,
+            &PredefinedMenuItem::separator(app)?,
+            &restart_item,
+            &logs_item,
+            &PredefinedMenuItem::separator(app)?,
+            &quit_item,
+        ],
+    )?;
+
+    *app.state::<ServerState>().tray_status_item.lock().unwrap() = Some(status_item);
+
+    TrayIconBuilder::with_id("main-tray")
+        .menu(&menu)
+        .tooltip("Server: starting…")
+        .on_menu_event(|app, event| match event.id.as_ref() {
+            "restart" => {
+                let state = app.state::<ServerState>();
+                if state.gave_up.load(Ordering::SeqCst) {
+                    // The supervisor task has already exited; kill_server would be a
+                    // no-op here, so start a fresh supervisor instead.
+                    spawn_server(app.clone());
+                } else {
+                    // Kill the child if one is running, and wake the supervisor in
+                    // case it's currently sleeping out a backoff delay with no
+                    // child to kill — either way this forces an immediate retry.
+                    kill_server(&state);
+                    state.restart_notify.notify_one();
+                }
+            }
+            "logs" => match app.get_webview_window("logs") {
+                Some(window) => {
+                    let _ = window.show();
+                    let _ = window.set_focus();
+                }
+                None => {
+                    let _ = WebviewWindowBuilder::new(app, "logs", WebviewUrl::App("logs.html".into()))
+                        .title("Server logs")
+                        .build();
+                }
+            },
+            "quit" => app.exit(0),
+            _ => {}
+        })
+        .build(app)?;
+
+    Ok(())
+}
+
+/// Writes a line-delimited message to the sidecar's stdin, e.g. a JSON-encoded
+/// control command the Node backend understands.
+#[tauri::command]
+fn send_to_server(state: tauri::State<ServerState>, msg: String) -> Result<(), String> {
+    let mut guard = state.child.lock().unwrap();
+    let child = guard.as_mut().ok_or("server is not running")?;
+    let mut line = msg;
+    line.push('\n');
+    child.write(line.as_bytes()).map_err(|err| err.to_string())
+}
+
+/// Returns the sidecar's dynamically assigned port once the readiness line has arrived.
+#[tauri::command]
+fn get_server_port(state: tauri::State<ServerState>) -> Option<u16> {
+    *state.port.lock().unwrap()
+}
 
 fn main() {
-    tauri::Builder::default()
+    let app = tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .manage(ServerState::new())
+        .invoke_handler(tauri::generate_handler![send_to_server, get_server_port])
         .setup(|app| {
-            // Spawn the Node.js backend server as a sidecar process
-            let sidecar = app.shell().sidecar("server")
-                .expect("failed to create sidecar command")
-                .args(&["--parent-pid", &std::process::id().to_string()]);
-            let (mut rx, _child) = sidecar.spawn()
-                .expect("failed to spawn server sidecar");
-
-            // Log sidecar stdout/stderr in background
-            tauri::async_runtime::spawn(async move {
-                while let Some(event) = rx.recv().await {
-                    match event {
-                        CommandEvent::Stdout(line) => {
-                            let s = String::from_utf8_lossy(&line);
-                            println!("[server] {}", s);
-                        }
-                        CommandEvent::Stderr(line) => {
-                            let s = String::from_utf8_lossy(&line);
-                            eprintln!("[server] {}", s);
-                        }
-                        CommandEvent::Terminated(status) => {
-                            eprintln!("[server] process terminated: {:?}", status);
+            let app_handle = app.handle().clone();
+
+            WebviewWindowBuilder::new(app, "splash", WebviewUrl::App("splash.html".into()))
+                .title("Starting...")
+                .inner_size(360.0, 200.0)
+                .resizable(false)
+                .decorations(false)
+                .center()
+                .build()?;
+
+            build_tray(app)?;
+
+            spawn_server(app_handle);
+
+            Ok(())
+        })
+        .build(tauri::generate_context!())
+        .expect("failed to build tauri app");
+
+    app.run(|app_handle, event| match event {
+        RunEvent::ExitRequested { .. } | RunEvent::Exit => {
+            let state = app_handle.state::<ServerState>();
+            state.shutting_down.store(true, Ordering::SeqCst);
+            kill_server(&state);
+        }
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::CloseRequested { .. },
+            ..
+        } if label == "main" => {
+            // Closing the main window tears the backend down, same as a real app
+            // exit; it is not merely hidden, so the backend is not left running
+            // unsupervised behind a closed window.
+            let state = app_handle.state::<ServerState>();
+            state.shutting_down.store(true, Ordering::SeqCst);
+            kill_server(&state);
+        }
+        _ => {}
+    });
+}
+
+/// Waits (with a timeout) for the sidecar to report readiness, then swaps the
+/// splash window for the main window. If the ready line never arrives, shows
+/// an error window instead of leaving the user staring at the splash forever.
+fn await_readiness(app: tauri::AppHandle, ready_rx: oneshot::Receiver<()>) {
+    tauri::async_runtime::spawn(async move {
+        match tokio::time::timeout(READY_TIMEOUT, ready_rx).await {
+            Ok(Ok(())) => {
+                if let Some(splash) = app.get_webview_window("splash") {
+                    let _ = splash.close();
+                }
+                if let Some(error) = app.get_webview_window("error") {
+                    let _ = error.close();
+                }
+                match app.get_webview_window("main") {
+                    Some(main) => {
+                        let _ = main.show();
+                    }
+                    None => {
+                        let _ =
+                            WebviewWindowBuilder::new(&app, "main", WebviewUrl::App("index.html".into()))
+                                .title("Agent UAC")
+                                .build();
+                    }
+                }
+                let _ = app.emit("server://ready", *app.state::<ServerState>().port.lock().unwrap());
+            }
+            result => {
+                match result {
+                    Err(_) => eprintln!("[server] timed out waiting for readiness after {:?}", READY_TIMEOUT),
+                    _ => eprintln!("[server] readiness wait ended: attempt superseded before SERVER_READY"),
+                }
+                if let Some(splash) = app.get_webview_window("splash") {
+                    let _ = splash.close();
+                }
+                show_error_window(&app, "Startup failed");
+            }
+        }
+    });
+}
+
+/// Spawns the `server` sidecar and supervises it for the lifetime of the app,
+/// re-spawning with exponential backoff whenever it terminates unexpectedly.
+fn spawn_server(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        app.state::<ServerState>().gave_up.store(false, Ordering::SeqCst);
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut retries = 0u32;
+        let shutting_down = app.state::<ServerState>().shutting_down.clone();
+        let restart_notify = app.state::<ServerState>().restart_notify.clone();
+
+        loop {
+            let max_retries = app.state::<ServerState>().max_retries.load(Ordering::SeqCst);
+
+            // Re-arm the readiness gate for this attempt. A fresh channel per
+            // attempt is needed because a previous attempt's `await_readiness`
+            // wait may already have timed out (dropping its receiver) while the
+            // supervisor kept retrying — without re-arming, a later SERVER_READY
+            // would `send` into a dead channel and the UI would stay stuck.
+            let (ready_tx, ready_rx) = oneshot::channel();
+            *app.state::<ServerState>().ready_tx.lock().unwrap() = Some(ready_tx);
+            await_readiness(app.clone(), ready_rx);
+
+            let sidecar = match app.shell().sidecar("server") {
+                Ok(cmd) => cmd.args(&["--parent-pid", &std::process::id().to_string()]),
+                Err(err) => {
+                    eprintln!("[server] failed to create sidecar command: {err}");
+                    break;
+                }
+            };
+
+            let (mut rx, child) = match sidecar.spawn() {
+                Ok(pair) => pair,
+                Err(err) => {
+                    eprintln!("[server] failed to spawn server sidecar: {err}");
+                    if !schedule_retry(&mut retries, &mut backoff, max_retries, &restart_notify).await {
+                        give_up(&app, max_retries);
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            *app.state::<ServerState>().child.lock().unwrap() = Some(child);
+
+            set_tray_status(&app, "starting");
+            let _ = app.emit("server://status", StatusEvent { state: "starting", code: None });
+
+            let started_at = Instant::now();
+
+            // Drain stdout/stderr/lifecycle events until the sidecar terminates
+            // or the channel is closed (whichever comes first).
+            while let Some(event) = rx.recv().await {
+                match event {
+                    CommandEvent::Stdout(line) => {
+                        let s = String::from_utf8_lossy(&line).to_string();
+                        println!("[server] {}", s);
+                        if let Some(port_str) = s.trim().strip_prefix(READY_PREFIX) {
+                            if let Ok(port) = port_str.trim().parse::<u16>() {
+                                *app.state::<ServerState>().port.lock().unwrap() = Some(port);
+                                if let Some(tx) = app.state::<ServerState>().ready_tx.lock().unwrap().take() {
+                                    let _ = tx.send(());
+                                }
+                                set_tray_status(&app, "ready");
+                                let _ = app.emit("server://status", StatusEvent { state: "ready", code: None });
+                            }
                         }
-                        _ => {}
+                        let _ = app.emit("server://stdout", LogEvent { line: s, timestamp: unix_millis() });
+                    }
+                    CommandEvent::Stderr(line) => {
+                        let s = String::from_utf8_lossy(&line).to_string();
+                        eprintln!("[server] {}", s);
+                        let _ = app.emit("server://stderr", LogEvent { line: s, timestamp: unix_millis() });
+                    }
+                    CommandEvent::Terminated(status) => {
+                        eprintln!("[server] process terminated: {:?}", status);
+                        *app.state::<ServerState>().port.lock().unwrap() = None;
+                        set_tray_status(&app, "terminated");
+                        let _ = app.emit(
+                            "server://status",
+                            StatusEvent { state: "terminated", code: status.code },
+                        );
+                        break;
                     }
+                    _ => {}
                 }
-            });
+            }
 
-            Ok(())
-        })
-        .run(tauri::generate_context!())
-        .expect("failed to run tauri app");
+            app.state::<ServerState>().child.lock().unwrap().take();
+
+            if shutting_down.load(Ordering::SeqCst) {
+                break;
+            }
+
+            if started_at.elapsed() >= HEALTHY_UPTIME {
+                retries = 0;
+                backoff = INITIAL_BACKOFF;
+            }
+
+            if !schedule_retry(&mut retries, &mut backoff, max_retries, &restart_notify).await {
+                give_up(&app, max_retries);
+                break;
+            }
+        }
+    });
+}
+
+/// Sleeps for the current backoff (or until `notify` fires, e.g. a manual tray
+/// restart), doubles the backoff (capped at `MAX_BACKOFF`), and bumps the
+/// retry counter. Returns `false` once `max_retries` has been exhausted.
+async fn schedule_retry(retries: &mut u32, backoff: &mut Duration, max_retries: u32, notify: &Notify) -> bool {
+    *retries += 1;
+    if *retries > max_retries {
+        return false;
+    }
+
+    tokio::select! {
+        _ = tokio::time::sleep(*backoff) => {}
+        _ = notify.notified() => {}
+    }
+    *backoff = (*backoff * 2).min(MAX_BACKOFF);
+    true
+}
+
+/// Marks the supervisor as having given up and surfaces that fatally to the
+/// user: tray status, a `server://status` event, and an error window (instead
+/// of just logging to stderr and leaving the app silently dead).
+fn give_up(app: &tauri::AppHandle, max_retries: u32) {
+    eprintln!("[server] giving up after {max_retries} failed restarts");
+    app.state::<ServerState>().gave_up.store(true, Ordering::SeqCst);
+    set_tray_status(app, "fatal");
+    let _ = app.emit("server://status", StatusEvent { state: "fatal", code: None });
+    show_error_window(app, "Server failed to start");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn doubles_backoff_up_to_cap() {
+        let notify = Notify::new();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut retries = 0;
+
+        assert!(schedule_retry(&mut retries, &mut backoff, DEFAULT_MAX_RETRIES, &notify).await);
+        assert_eq!(retries, 1);
+        assert_eq!(backoff, INITIAL_BACKOFF * 2);
+
+        while backoff < MAX_BACKOFF {
+            assert!(schedule_retry(&mut retries, &mut backoff, u32::MAX, &notify).await);
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
+
+        assert!(schedule_retry(&mut retries, &mut backoff, u32::MAX, &notify).await);
+        assert_eq!(backoff, MAX_BACKOFF, "backoff must not grow past the cap");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_once_max_retries_exhausted() {
+        let notify = Notify::new();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut retries = 0;
+
+        for _ in 0..3 {
+            assert!(schedule_retry(&mut retries, &mut backoff, 3, &notify).await);
+        }
+        assert_eq!(retries, 3);
+
+        assert!(!schedule_retry(&mut retries, &mut backoff, 3, &notify).await);
+        assert_eq!(retries, 4, "the attempt that tips over max_retries still counts");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notify_interrupts_the_backoff_sleep_immediately() {
+        let notify = Notify::new();
+        let mut backoff = Duration::from_secs(10);
+        let mut retries = 0;
+
+        notify.notify_one();
+        let start = tokio::time::Instant::now();
+        // Resolves immediately instead of waiting out the 10s backoff, since a
+        // permit was already stored before `schedule_retry` started waiting.
+        assert!(schedule_retry(&mut retries, &mut backoff, DEFAULT_MAX_RETRIES, &notify).await);
+        assert!(start.elapsed() < Duration::from_secs(10), "the backoff sleep was not interrupted");
+    }
 }